@@ -1,6 +1,8 @@
 use std::{
+    borrow::Borrow,
     collections::{hash_map::RandomState, HashMap},
-    hash::Hash,
+    fmt,
+    hash::{BuildHasher, Hash},
 };
 
 pub struct StableHashMap<K, V, H = RandomState>
@@ -10,6 +12,7 @@ where
 {
     hashmap: HashMap<usize, V, H>,
     key_vec: Vec<K>,
+    key_index: HashMap<K, usize>,
 }
 
 impl<K, V, H> StableHashMap<K, V, H>
@@ -22,6 +25,7 @@ where
         Self {
             hashmap: HashMap::with_hasher(hash_builder),
             key_vec: Vec::new(),
+            key_index: HashMap::new(),
         }
     }
 
@@ -29,7 +33,8 @@ where
     pub fn with_capacity_and_hasher(capacity: usize, hash_builder: H) -> Self {
         Self {
             hashmap: HashMap::with_capacity_and_hasher(capacity, hash_builder),
-            key_vec: Vec::new(),
+            key_vec: Vec::with_capacity(capacity),
+            key_index: HashMap::with_capacity(capacity),
         }
     }
 }
@@ -44,6 +49,7 @@ where
         Self {
             hashmap: HashMap::new(),
             key_vec: Vec::new(),
+            key_index: HashMap::new(),
         }
     }
 
@@ -52,16 +58,36 @@ where
         Self {
             hashmap: HashMap::with_capacity(size),
             key_vec: Vec::with_capacity(size),
+            key_index: HashMap::with_capacity(size),
         }
     }
+}
 
+impl<K, V, H> StableHashMap<K, V, H>
+where
+    K: Clone + Eq + PartialEq + Hash,
+    V: Clone,
+    H: BuildHasher,
+{
+    /// Appends `key`/`value`, assigning the next stable index. If `key` is
+    /// already present its value is updated in place instead, so pushing never
+    /// gives a key a second index and keeps `key_index` in step with
+    /// `key_vec`/`hashmap`.
     pub fn push(&mut self, key: K, value: V) {
-        self.hashmap.insert(self.key_vec.len(), value);
+        if let Some(&idx) = self.key_index.get(&key) {
+            self.hashmap.insert(idx, value);
+            return;
+        }
+
+        let idx = self.key_vec.len();
+        self.hashmap.insert(idx, value);
+        self.key_index.insert(key.clone(), idx);
         self.key_vec.push(key);
     }
 
     pub fn pop(&mut self) -> Option<(K, V)> {
         self.key_vec.pop().and_then(|key| {
+            self.key_index.remove(&key);
             self.hashmap
                 .remove(&self.key_vec.len())
                 .map(|val| (key, val))
@@ -85,19 +111,341 @@ where
     }
 
     #[must_use]
-    pub fn get_by_key(&self, key: &K) -> Option<&V> {
-        self.key_vec
-            .iter()
-            .position(|k| k == key)
-            .and_then(|idx| self.hashmap.get(&idx))
+    pub fn get_by_key<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.key_index
+            .get(key)
+            .and_then(|idx| self.hashmap.get(idx))
     }
 
     #[must_use]
-    pub fn get_mut_by_key(&mut self, key: &K) -> Option<&mut V> {
-        self.key_vec
-            .iter()
-            .position(|k| k == key)
-            .and_then(|idx| self.hashmap.get_mut(&idx))
+    pub fn get_mut_by_key<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.key_index
+            .get(key)
+            .and_then(|idx| self.hashmap.get_mut(idx))
+    }
+
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V, H> {
+        match self.key_index.get(&key).copied() {
+            Some(idx) => Entry::Occupied(OccupiedEntry { map: self, idx }),
+            None => Entry::Vacant(VacantEntry { map: self, key }),
+        }
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        self.hashmap.reserve(additional);
+        self.key_vec.reserve(additional);
+        self.key_index.reserve(additional);
+    }
+
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        self.hashmap.try_reserve(additional)?;
+        self.key_vec.try_reserve(additional)?;
+        self.key_index.try_reserve(additional)?;
+        Ok(())
+    }
+
+    /// Removes the entry at `idx`, shifting every subsequent stable index down by
+    /// one so iteration order stays dense. O(n); see [`Self::swap_remove`] for an
+    /// O(1) variant that does not preserve order.
+    pub fn remove(&mut self, idx: usize) -> Option<(K, V)> {
+        if idx >= self.key_vec.len() {
+            return None;
+        }
+
+        let key = self.key_vec.remove(idx);
+        let value = self.hashmap.remove(&idx)?;
+        self.key_index.remove(&key);
+
+        for shifted in idx..self.key_vec.len() {
+            if let Some(moved_value) = self.hashmap.remove(&(shifted + 1)) {
+                self.hashmap.insert(shifted, moved_value);
+            }
+            self.key_index.insert(self.key_vec[shifted].clone(), shifted);
+        }
+
+        Some((key, value))
+    }
+
+    /// Removes the entry for `key`, shifting subsequent stable indices down by one.
+    pub fn remove_by_key<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let idx = *self.key_index.get(key)?;
+        self.remove(idx).map(|(_, value)| value)
+    }
+
+    /// Removes the entry at `idx` in O(1) by swapping it with the last entry,
+    /// which does not preserve the relative order of the remaining entries.
+    pub fn swap_remove(&mut self, idx: usize) -> Option<(K, V)> {
+        let last = self.key_vec.len().checked_sub(1)?;
+        if idx > last {
+            return None;
+        }
+
+        self.key_vec.swap(idx, last);
+        let key = self.key_vec.pop()?;
+        self.key_index.remove(&key);
+
+        let value = if idx == last {
+            self.hashmap.remove(&last)?
+        } else {
+            let moved_value = self.hashmap.remove(&last)?;
+            let value = self.hashmap.insert(idx, moved_value)?;
+            self.key_index.insert(self.key_vec[idx].clone(), idx);
+            value
+        };
+
+        Some((key, value))
+    }
+
+    #[must_use]
+    pub fn iter(&self) -> StableHashMapIter<'_, K, V, H> {
+        StableHashMapIter {
+            stable_map: self,
+            index: 0,
+        }
+    }
+
+    #[must_use]
+    pub fn iter_mut(&mut self) -> StableHashMapIterMut<'_, K, V> {
+        let mut values: Vec<(usize, &mut V)> = self
+            .hashmap
+            .iter_mut()
+            .map(|(&idx, value)| (idx, value))
+            .collect();
+        values.sort_unstable_by_key(|(idx, _)| *idx);
+        let values: Vec<&mut V> = values.into_iter().map(|(_, value)| value).collect();
+
+        StableHashMapIterMut {
+            keys: self.key_vec.iter(),
+            values: values.into_iter(),
+        }
+    }
+
+    /// Keeps only the entries for which `f` returns `true`, preserving the
+    /// relative order of the survivors. Single O(n) pass that compacts
+    /// `key_vec`/`hashmap`/`key_index` in place, rather than calling
+    /// [`Self::remove`] per dropped element (which would re-shift the
+    /// remainder on every call).
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        let len = self.key_vec.len();
+        let mut write = 0;
+
+        for read in 0..len {
+            let mut value = self
+                .hashmap
+                .remove(&read)
+                .expect("every index in 0..len has a value");
+            let keep = f(&self.key_vec[read], &mut value);
+
+            if keep {
+                if write != read {
+                    self.key_vec.swap(write, read);
+                }
+                self.key_index.insert(self.key_vec[write].clone(), write);
+                self.hashmap.insert(write, value);
+                write += 1;
+            } else {
+                self.key_index.remove(&self.key_vec[read]);
+            }
+        }
+
+        self.key_vec.truncate(write);
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.key_vec.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.key_vec.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.hashmap.clear();
+        self.key_vec.clear();
+        self.key_index.clear();
+    }
+
+    #[must_use]
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.key_index.contains_key(key)
+    }
+}
+
+/// Wraps [`std::collections::TryReserveError`] so a failure to grow any of the
+/// backing containers is reported uniformly, regardless of which one failed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TryReserveError(std::collections::TryReserveError);
+
+impl From<std::collections::TryReserveError> for TryReserveError {
+    fn from(err: std::collections::TryReserveError) -> Self {
+        Self(err)
+    }
+}
+
+impl fmt::Display for TryReserveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl std::error::Error for TryReserveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+/// A view into a single entry in a [`StableHashMap`], obtained via [`StableHashMap::entry`].
+pub enum Entry<'a, K, V, H>
+where
+    K: Clone + Eq + PartialEq + Hash,
+    V: Clone,
+{
+    Occupied(OccupiedEntry<'a, K, V, H>),
+    Vacant(VacantEntry<'a, K, V, H>),
+}
+
+impl<'a, K, V, H> Entry<'a, K, V, H>
+where
+    K: Clone + Eq + PartialEq + Hash,
+    V: Clone,
+    H: BuildHasher,
+{
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default),
+        }
+    }
+
+    pub fn or_insert_with<F>(self, default: F) -> &'a mut V
+    where
+        F: FnOnce() -> V,
+    {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    pub fn and_modify<F>(self, f: F) -> Self
+    where
+        F: FnOnce(&mut V),
+    {
+        match self {
+            Entry::Occupied(mut entry) => {
+                f(entry.get_mut());
+                Entry::Occupied(entry)
+            }
+            Entry::Vacant(entry) => Entry::Vacant(entry),
+        }
+    }
+}
+
+impl<'a, K, V, H> Entry<'a, K, V, H>
+where
+    K: Clone + Eq + PartialEq + Hash,
+    V: Clone + Default,
+    H: BuildHasher,
+{
+    pub fn or_default(self) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(V::default()),
+        }
+    }
+}
+
+pub struct OccupiedEntry<'a, K, V, H>
+where
+    K: Clone + Eq + PartialEq + Hash,
+    V: Clone,
+{
+    map: &'a mut StableHashMap<K, V, H>,
+    idx: usize,
+}
+
+impl<'a, K, V, H> OccupiedEntry<'a, K, V, H>
+where
+    K: Clone + Eq + PartialEq + Hash,
+    V: Clone,
+    H: BuildHasher,
+{
+    #[must_use]
+    pub fn key(&self) -> &K {
+        &self.map.key_vec[self.idx]
+    }
+
+    #[must_use]
+    pub fn get(&self) -> &V {
+        self.map
+            .hashmap
+            .get(&self.idx)
+            .expect("occupied entry refers to a live index")
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        self.map
+            .hashmap
+            .get_mut(&self.idx)
+            .expect("occupied entry refers to a live index")
+    }
+
+    pub fn into_mut(self) -> &'a mut V {
+        self.map
+            .hashmap
+            .get_mut(&self.idx)
+            .expect("occupied entry refers to a live index")
+    }
+}
+
+pub struct VacantEntry<'a, K, V, H>
+where
+    K: Clone + Eq + PartialEq + Hash,
+    V: Clone,
+{
+    map: &'a mut StableHashMap<K, V, H>,
+    key: K,
+}
+
+impl<'a, K, V, H> VacantEntry<'a, K, V, H>
+where
+    K: Clone + Eq + PartialEq + Hash,
+    V: Clone,
+    H: BuildHasher,
+{
+    #[must_use]
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn insert(self, value: V) -> &'a mut V {
+        let idx = self.map.key_vec.len();
+        self.map.push(self.key, value);
+        self.map
+            .hashmap
+            .get_mut(&idx)
+            .expect("just inserted this index")
     }
 }
 
@@ -111,19 +459,58 @@ where
     }
 }
 
-pub struct StableHashMapIntoIterator<K, V>
+pub struct StableHashMapIter<'a, K, V, H = RandomState>
+where
+    K: Clone + Eq + PartialEq + Hash,
+    V: Clone,
+{
+    stable_map: &'a StableHashMap<K, V, H>,
+    index: usize,
+}
+
+impl<'a, K, V, H> Iterator for StableHashMapIter<'a, K, V, H>
+where
+    K: Clone + Eq + PartialEq + Hash,
+    V: Clone,
+    H: BuildHasher,
+{
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.stable_map.get(self.index);
+        self.index += 1;
+
+        result
+    }
+}
+
+pub struct StableHashMapIterMut<'a, K, V> {
+    keys: std::slice::Iter<'a, K>,
+    values: std::vec::IntoIter<&'a mut V>,
+}
+
+impl<'a, K, V> Iterator for StableHashMapIterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some((self.keys.next()?, self.values.next()?))
+    }
+}
+
+pub struct StableHashMapIntoIterator<K, V, H = RandomState>
 where
     K: Clone + Eq + PartialEq + Hash,
     V: Clone,
 {
-    stable_map: StableHashMap<K, V>,
+    stable_map: StableHashMap<K, V, H>,
     index: usize,
 }
 
-impl<K, V> Iterator for StableHashMapIntoIterator<K, V>
+impl<K, V, H> Iterator for StableHashMapIntoIterator<K, V, H>
 where
     K: Clone + Eq + PartialEq + Hash,
     V: Clone,
+    H: BuildHasher,
 {
     type Item = (K, V);
 
@@ -140,13 +527,14 @@ where
     }
 }
 
-impl<K, V> IntoIterator for StableHashMap<K, V>
+impl<K, V, H> IntoIterator for StableHashMap<K, V, H>
 where
     K: Clone + Eq + PartialEq + Hash,
     V: Clone,
+    H: BuildHasher,
 {
     type Item = (K, V);
-    type IntoIter = StableHashMapIntoIterator<K, V>;
+    type IntoIter = StableHashMapIntoIterator<K, V, H>;
 
     fn into_iter(self) -> Self::IntoIter {
         Self::IntoIter {
@@ -156,34 +544,489 @@ where
     }
 }
 
-impl<K, V> From<&[(K, V)]> for StableHashMap<K, V>
+impl<K, V, H> Extend<(K, V)> for StableHashMap<K, V, H>
+where
+    K: Clone + Eq + PartialEq + Hash,
+    V: Clone,
+    H: BuildHasher,
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.push(key, value);
+        }
+    }
+}
+
+impl<K, V, H> FromIterator<(K, V)> for StableHashMap<K, V, H>
+where
+    K: Clone + Eq + PartialEq + Hash,
+    V: Clone,
+    H: BuildHasher + Default,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Self::with_hasher(H::default());
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K, V, H> From<&[(K, V)]> for StableHashMap<K, V, H>
 where
     K: Clone + Eq + PartialEq + Hash,
     V: Clone,
+    H: BuildHasher + Default,
 {
     fn from(tuples: &[(K, V)]) -> Self {
-        let key_vec: Vec<K> = tuples.iter().map(|(k, _)| k.clone()).collect();
-        let hashmap: HashMap<usize, V> = tuples
-            .iter()
-            .enumerate()
-            .map(|(idx, (_, v))| (idx, v.clone()))
-            .collect();
-        Self { hashmap, key_vec }
+        let mut map = Self::with_hasher(H::default());
+        map.extend(tuples.iter().cloned());
+        map
     }
 }
 
-impl<K, V> From<Vec<(K, V)>> for StableHashMap<K, V>
+impl<K, V, H> From<Vec<(K, V)>> for StableHashMap<K, V, H>
 where
     K: Clone + Eq + PartialEq + Hash,
     V: Clone,
+    H: BuildHasher + Default,
 {
     fn from(tuples: Vec<(K, V)>) -> Self {
-        let key_vec: Vec<K> = tuples.clone().into_iter().map(|(k, _)| k).collect();
-        let hashmap: HashMap<usize, V> = tuples
-            .into_iter()
-            .enumerate()
-            .map(|(usize, (_, v))| (usize, v))
-            .collect();
-        Self { hashmap, key_vec }
+        let mut map = Self::with_hasher(H::default());
+        map.extend(tuples);
+        map
+    }
+}
+
+#[cfg(feature = "serde")]
+mod serde_impl {
+    use super::{BuildHasher, Hash, StableHashMap};
+    use std::{fmt, marker::PhantomData};
+
+    use serde::{
+        de::{Deserialize, Deserializer, SeqAccess, Visitor},
+        ser::{Serialize, SerializeSeq, Serializer},
+    };
+
+    impl<K, V, H> Serialize for StableHashMap<K, V, H>
+    where
+        K: Clone + Eq + PartialEq + Hash + Serialize,
+        V: Clone + Serialize,
+        H: BuildHasher,
+    {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            let mut seq = serializer.serialize_seq(Some(self.key_vec.len()))?;
+            for (key, value) in self.iter() {
+                seq.serialize_element(&(key, value))?;
+            }
+            seq.end()
+        }
+    }
+
+    struct StableHashMapVisitor<K, V, H>(PhantomData<(K, V, H)>);
+
+    impl<'de, K, V, H> Visitor<'de> for StableHashMapVisitor<K, V, H>
+    where
+        K: Clone + Eq + PartialEq + Hash + Deserialize<'de>,
+        V: Clone + Deserialize<'de>,
+        H: BuildHasher + Default,
+    {
+        type Value = StableHashMap<K, V, H>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+            formatter.write_str("a sequence of key-value pairs")
+        }
+
+        fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+        where
+            A: SeqAccess<'de>,
+        {
+            let mut map = StableHashMap::with_capacity_and_hasher(
+                seq.size_hint().unwrap_or(0),
+                H::default(),
+            );
+            while let Some((key, value)) = seq.next_element::<(K, V)>()? {
+                map.push(key, value);
+            }
+            Ok(map)
+        }
+    }
+
+    impl<'de, K, V, H> Deserialize<'de> for StableHashMap<K, V, H>
+    where
+        K: Clone + Eq + PartialEq + Hash + Deserialize<'de>,
+        V: Clone + Deserialize<'de>,
+        H: BuildHasher + Default,
+    {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            deserializer.deserialize_seq(StableHashMapVisitor(PhantomData))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A non-`RandomState` `BuildHasher`, used to prove the generic
+    /// `impl<K, V, H: BuildHasher>` block is actually reachable.
+    #[derive(Clone, Default)]
+    struct CustomHasher;
+
+    impl BuildHasher for CustomHasher {
+        type Hasher = std::collections::hash_map::DefaultHasher;
+
+        fn build_hasher(&self) -> Self::Hasher {
+            std::collections::hash_map::DefaultHasher::new()
+        }
+    }
+
+    #[test]
+    fn with_hasher_produces_a_fully_working_map() {
+        let mut map: StableHashMap<&str, i32, CustomHasher> =
+            StableHashMap::with_hasher(CustomHasher);
+        map.push("a", 1);
+        map.push("b", 2);
+
+        assert_eq!(map.get(0), Some((&"a", &1)));
+        assert_eq!(map.get_by_key("a"), Some(&1));
+        assert_eq!(map.get_mut_by_key("b"), Some(&mut 2));
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn with_capacity_and_hasher_produces_a_working_map() {
+        let mut map: StableHashMap<&str, i32, CustomHasher> =
+            StableHashMap::with_capacity_and_hasher(4, CustomHasher);
+        map.push("x", 10);
+
+        assert_eq!(map.get_by_key("x"), Some(&10));
+        assert_eq!(map.remove_by_key("x"), Some(10));
+    }
+
+    #[test]
+    fn get_by_key_accepts_borrowed_str() {
+        let mut map: StableHashMap<String, i32> = StableHashMap::new();
+        map.push("a".to_string(), 1);
+
+        assert_eq!(map.get_by_key("a"), Some(&1));
+        assert_eq!(map.get_mut_by_key("a"), Some(&mut 1));
+        assert!(map.contains_key("a"));
+        assert_eq!(map.get_by_key("missing"), None);
+    }
+
+    #[test]
+    fn get_by_key_is_index_based_not_linear() {
+        let mut map: StableHashMap<i32, &str> = StableHashMap::new();
+        for i in 0..100 {
+            map.push(i, "value");
+        }
+
+        assert_eq!(map.get_by_key(&99), Some(&"value"));
+    }
+
+    #[test]
+    fn pushing_an_existing_key_updates_in_place_instead_of_duplicating() {
+        let mut map: StableHashMap<&str, i32> = StableHashMap::new();
+        map.push("dup", 1);
+        map.push("dup", 2);
+
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.get_by_key("dup"), Some(&2));
+        assert_eq!(map.remove_by_key("dup"), Some(2));
+        assert!(!map.contains_key("dup"));
+        assert_eq!(map.iter().count(), 0);
+    }
+
+    #[test]
+    fn from_vec_and_from_slice_dedupe_like_push() {
+        let from_vec: StableHashMap<&str, i32> = StableHashMap::from(vec![("a", 1), ("a", 2)]);
+        assert_eq!(from_vec.len(), 1);
+        assert_eq!(from_vec.get_by_key("a"), Some(&2));
+        assert!(from_vec.contains_key("a"));
+
+        let tuples = [("b", 1), ("b", 2)];
+        let from_slice: StableHashMap<&str, i32> = StableHashMap::from(&tuples[..]);
+        assert_eq!(from_slice.len(), 1);
+        assert_eq!(from_slice.get_by_key("b"), Some(&2));
+        assert!(from_slice.contains_key("b"));
+    }
+
+    #[test]
+    fn entry_or_insert_creates_then_reuses_the_same_slot() {
+        let mut map: StableHashMap<&str, i32> = StableHashMap::new();
+
+        *map.entry("a").or_insert(1) += 10;
+        *map.entry("a").or_insert(100) += 10;
+
+        assert_eq!(map.get_by_key("a"), Some(&21));
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn entry_or_insert_with_only_calls_the_closure_when_vacant() {
+        let mut map: StableHashMap<&str, i32> = StableHashMap::new();
+        map.push("a", 1);
+
+        let mut calls = 0;
+        *map.entry("a").or_insert_with(|| {
+            calls += 1;
+            99
+        }) += 1;
+        map.entry("b").or_insert_with(|| {
+            calls += 1;
+            99
+        });
+
+        assert_eq!(calls, 1);
+        assert_eq!(map.get_by_key("a"), Some(&2));
+        assert_eq!(map.get_by_key("b"), Some(&99));
+    }
+
+    #[test]
+    fn entry_or_default_and_and_modify() {
+        let mut map: StableHashMap<&str, i32> = StableHashMap::new();
+
+        map.entry("counter").or_default();
+        map.entry("counter").and_modify(|v| *v += 1).or_default();
+        map.entry("counter").and_modify(|v| *v += 1).or_default();
+
+        assert_eq!(map.get_by_key("counter"), Some(&2));
+    }
+
+    #[test]
+    fn reserve_grows_capacity_and_keeps_the_map_usable() {
+        let mut map: StableHashMap<&str, i32> = StableHashMap::new();
+        map.reserve(16);
+        map.push("a", 1);
+
+        assert_eq!(map.get_by_key("a"), Some(&1));
+    }
+
+    #[test]
+    fn try_reserve_succeeds_for_a_reasonable_request() {
+        let mut map: StableHashMap<&str, i32> = StableHashMap::new();
+
+        assert!(map.try_reserve(16).is_ok());
+        map.push("a", 1);
+        assert_eq!(map.get_by_key("a"), Some(&1));
+    }
+
+    #[test]
+    fn try_reserve_reports_overflow_as_an_error_instead_of_panicking() {
+        let mut map: StableHashMap<&str, i32> = StableHashMap::new();
+
+        let err = map
+            .try_reserve(usize::MAX)
+            .expect_err("must not allocate usize::MAX entries");
+        assert!(!err.to_string().is_empty());
+    }
+
+    fn sample() -> StableHashMap<&'static str, i32> {
+        let mut map = StableHashMap::new();
+        for (key, value) in [("a", 1), ("b", 2), ("c", 3), ("d", 4)] {
+            map.push(key, value);
+        }
+        map
+    }
+
+    #[test]
+    fn iter_yields_entries_in_index_order() {
+        let map = sample();
+
+        assert_eq!(
+            map.iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>(),
+            vec![("a", 1), ("b", 2), ("c", 3), ("d", 4)]
+        );
+    }
+
+    #[test]
+    fn iter_mut_mutates_values_visible_through_get_and_get_by_key() {
+        let mut map = sample();
+
+        for (_, value) in map.iter_mut() {
+            *value *= 10;
+        }
+
+        assert_eq!(
+            map.iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>(),
+            vec![("a", 10), ("b", 20), ("c", 30), ("d", 40)]
+        );
+        assert_eq!(map.get(2), Some((&"c", &30)));
+        assert_eq!(map.get_by_key("d"), Some(&40));
+    }
+
+    #[test]
+    fn remove_shifts_subsequent_indices_down_and_preserves_order() {
+        let mut map = sample();
+
+        assert_eq!(map.remove(1), Some(("b", 2)));
+        assert_eq!(
+            map.iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>(),
+            vec![("a", 1), ("c", 3), ("d", 4)]
+        );
+        assert_eq!(map.get(0), Some((&"a", &1)));
+        assert_eq!(map.get(1), Some((&"c", &3)));
+        assert_eq!(map.get(2), Some((&"d", &4)));
+        assert_eq!(map.get_by_key("c"), Some(&3));
+        assert!(!map.contains_key("b"));
+    }
+
+    #[test]
+    fn remove_by_key_looks_up_the_index_to_remove() {
+        let mut map = sample();
+
+        assert_eq!(map.remove_by_key("c"), Some(3));
+        assert_eq!(
+            map.iter().map(|(&k, _)| k).collect::<Vec<_>>(),
+            vec!["a", "b", "d"]
+        );
+        assert_eq!(map.remove_by_key("missing"), None);
+    }
+
+    #[test]
+    fn swap_remove_is_o1_and_does_not_preserve_order() {
+        let mut map = sample();
+
+        assert_eq!(map.swap_remove(0), Some(("a", 1)));
+        assert_eq!(
+            map.iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>(),
+            vec![("d", 4), ("b", 2), ("c", 3)]
+        );
+        assert_eq!(map.get_by_key("d"), Some(&4));
+        assert!(!map.contains_key("a"));
+    }
+
+    #[test]
+    fn swap_remove_last_element_is_a_plain_pop() {
+        let mut map = sample();
+
+        assert_eq!(map.swap_remove(3), Some(("d", 4)));
+        assert_eq!(
+            map.iter().map(|(&k, _)| k).collect::<Vec<_>>(),
+            vec!["a", "b", "c"]
+        );
+    }
+
+    #[test]
+    fn retain_keeps_only_matching_entries_in_order() {
+        let mut map = sample();
+
+        map.retain(|_, v| *v % 2 == 0);
+
+        assert_eq!(
+            map.iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>(),
+            vec![("b", 2), ("d", 4)]
+        );
+        assert_eq!(map.len(), 2);
+        assert!(map.contains_key("b"));
+        assert!(map.contains_key("d"));
+        assert!(!map.contains_key("a"));
+        assert!(!map.contains_key("c"));
+        // Reverse index must still resolve to the post-compaction positions.
+        assert_eq!(map.get(0), Some((&"b", &2)));
+        assert_eq!(map.get(1), Some((&"d", &4)));
+        assert_eq!(map.get_by_key("d"), Some(&4));
+    }
+
+    #[test]
+    fn retain_dropping_everything_leaves_an_empty_and_consistent_map() {
+        let mut map = sample();
+
+        map.retain(|_, _| false);
+
+        assert!(map.is_empty());
+        assert_eq!(map.len(), 0);
+        assert_eq!(map.iter().count(), 0);
+        for key in ["a", "b", "c", "d"] {
+            assert!(!map.contains_key(key));
+        }
+
+        map.push("e", 5);
+        assert_eq!(map.get_by_key("e"), Some(&5));
+    }
+
+    #[test]
+    fn retain_keeping_everything_is_a_no_op() {
+        let mut map = sample();
+
+        map.retain(|_, _| true);
+
+        assert_eq!(
+            map.iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>(),
+            vec![("a", 1), ("b", 2), ("c", 3), ("d", 4)]
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip_preserves_insertion_order() {
+        let map = sample();
+
+        let json = serde_json::to_string(&map).expect("serialize");
+        assert_eq!(json, r#"[["a",1],["b",2],["c",3],["d",4]]"#);
+
+        let round_tripped: StableHashMap<String, i32> =
+            serde_json::from_str(&json).expect("deserialize");
+
+        assert_eq!(
+            round_tripped.iter().map(|(k, &v)| (k.clone(), v)).collect::<Vec<_>>(),
+            vec![
+                ("a".to_string(), 1),
+                ("b".to_string(), 2),
+                ("c".to_string(), 3),
+                ("d".to_string(), 4),
+            ]
+        );
+        assert_eq!(round_tripped.get_by_key("c"), Some(&3));
+        assert_eq!(round_tripped.get(1), Some((&"b".to_string(), &2)));
+    }
+
+    #[test]
+    fn from_iter_collects_in_iteration_order_with_consecutive_indices() {
+        let map: StableHashMap<&str, i32> =
+            [("a", 1), ("b", 2), ("c", 3)].into_iter().collect();
+
+        assert_eq!(map.len(), 3);
+        assert_eq!(
+            map.iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>(),
+            vec![("a", 1), ("b", 2), ("c", 3)]
+        );
+        assert_eq!(map.get(1), Some((&"b", &2)));
+        assert_eq!(map.get_by_key("c"), Some(&3));
+    }
+
+    #[test]
+    fn extend_appends_and_dedupes_like_push() {
+        let mut map = sample();
+
+        map.extend([("e", 5), ("a", 100)]);
+
+        assert_eq!(map.len(), 5);
+        assert_eq!(
+            map.iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>(),
+            vec![("a", 100), ("b", 2), ("c", 3), ("d", 4), ("e", 5)]
+        );
+    }
+
+    #[test]
+    fn len_is_empty_and_clear() {
+        let mut map = sample();
+        assert_eq!(map.len(), 4);
+        assert!(!map.is_empty());
+
+        map.clear();
+
+        assert!(map.is_empty());
+        assert_eq!(map.len(), 0);
+        assert_eq!(map.iter().count(), 0);
+        assert!(!map.contains_key("a"));
+
+        map.push("a", 1);
+        assert_eq!(map.get_by_key("a"), Some(&1));
     }
 }